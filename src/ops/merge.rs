@@ -3,14 +3,16 @@ use super::{
     OpTrait,
 };
 use crate::{git, items::TargetData, menu::arg::Arg, state::State, term::Term, Res};
-use std::{convert::Infallible, fmt::Display, process::Command, rc::Rc, str::FromStr};
+use std::{fmt::Display, process::Command, rc::Rc, str::FromStr};
 
 // key for merge and rebase: "-s"
 // key for cherry-pick and revert: "=s"
 // shortarg for merge and rebase: "-s"
 // shortarg for cherry-pick and revert: none
 
-#[derive(Debug)]
+/// The `-s`/`--strategy=` values accepted by `git-merge`, cycled through via
+/// the menu like `magit-merge:--strategy`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum StrategyArgValue {
     Resolve,
     Recursive,
@@ -19,22 +21,63 @@ enum StrategyArgValue {
     Subtree,
 }
 
+impl StrategyArgValue {
+    const ALL: [StrategyArgValue; 5] = [
+        StrategyArgValue::Resolve,
+        StrategyArgValue::Recursive,
+        StrategyArgValue::Octopus,
+        StrategyArgValue::Ours,
+        StrategyArgValue::Subtree,
+    ];
+}
+
 impl FromStr for StrategyArgValue {
-    type Err = Infallible;
+    type Err = String;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
-            _ => Ok(StrategyArgValue::Ours),
+            "resolve" => Ok(StrategyArgValue::Resolve),
+            "recursive" => Ok(StrategyArgValue::Recursive),
+            "octopus" => Ok(StrategyArgValue::Octopus),
+            "ours" => Ok(StrategyArgValue::Ours),
+            "subtree" => Ok(StrategyArgValue::Subtree),
+            other => Err(format!("unknown merge strategy: {}", other)),
         }
     }
 }
 
+impl Display for StrategyArgValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            StrategyArgValue::Resolve => "resolve",
+            StrategyArgValue::Recursive => "recursive",
+            StrategyArgValue::Octopus => "octopus",
+            StrategyArgValue::Ours => "ours",
+            StrategyArgValue::Subtree => "subtree",
+        })
+    }
+}
+
 pub(crate) fn init_args() -> Vec<Arg> {
     vec![
-        Arg::new_flag("--ff-only", "Fast-forward only", false),
-        Arg::new_flag("--no-ff", "No fast-forward", false),
-        // FIXME: Include Strategy before merging.
-        // Arg::new_arg("--strategy=", "Strategy", None, StrategyArgValue::from_str),
+        Arg::new_flag("--ff-only", "Fast-forward only", false).incompatible_with(&["--no-ff"]),
+        Arg::new_flag("--no-ff", "No fast-forward", false).incompatible_with(&["--ff-only"]),
+        // `None` leaves the arg unset (and out of `args()`) until the user
+        // cycles it, same as `Arg::new_arg`'s `None` default below — it must
+        // not silently override git's own strategy selection for merges
+        // where the user never touched this arg.
+        Arg::new_cycle("--strategy=", "Strategy", &StrategyArgValue::ALL, None),
+        Arg::new_flag(
+            "-Xignore-space-change",
+            "Ignore changes in amount of whitespace",
+            false,
+        ),
+        Arg::new_flag(
+            "-Xignore-all-space",
+            "Ignore whitespace when comparing lines",
+            false,
+        ),
+        Arg::new_arg("-m", "Message", None),
     ]
 }
 
@@ -58,7 +101,8 @@ pub(crate) enum MergeAction {
     Edit,
     NoCommit,
     Absorb,
-    // FIXME: Implement Preview.
+    Preview,
+    Message,
     Squash,
     Dissolve,
 }
@@ -95,12 +139,99 @@ impl MergeAction {
         state.run_cmd_interactive(term, cmd)
     }
 
-    // FIXME: This implementation is unfinished. This is one of the most
-    // complex merge commands, so leaving for one of the later implementations.
-    //
-    // Ref: <https://github.com/magit/magit/blob/main/lisp/magit-merge.el#L171>
-    fn absorb(_state: &mut State, _term: &mut Term, _branch_name: &str) -> Res<()> {
-        todo!()
+    /// Merge `branch_name` into the current branch without an edit commit,
+    /// then delete `branch_name` on success, like absorbing a finished PR.
+    ///
+    /// Ref: <https://github.com/magit/magit/blob/main/lisp/magit-merge.el#L171>
+    fn absorb(state: &mut State, term: &mut Term, branch_name: &str) -> Res<()> {
+        let mut cmd = Command::new("git");
+        let args = state.pending_menu.as_ref().unwrap().args();
+        cmd.args(["merge", "-m"]);
+        cmd.arg(format!("Merge branch '{}'", branch_name));
+        if !args
+            .iter()
+            .any(|arg| arg == "--no-ff" || arg == "--ff-only")
+        {
+            cmd.arg("--no-ff");
+        }
+        cmd.args(args);
+        cmd.args([branch_name]);
+        state.close_menu();
+        state.run_cmd(term, &[], cmd)?;
+
+        let mut delete_cmd = Command::new("git");
+        delete_cmd.args(["branch", "-d", branch_name]);
+        state.run_cmd(term, &[], delete_cmd)
+    }
+
+    /// Show what a merge of `rev` into `HEAD` would look like, without
+    /// touching the working tree or index.
+    ///
+    /// `git merge-tree --write-tree` computes the merge entirely in-memory
+    /// and prints the resulting tree's OID; diffing that tree against `HEAD`
+    /// gives the patch the merge would introduce, which is rendered in the
+    /// diff view like any other diff buffer.
+    fn preview(state: &mut State, term: &mut Term, rev: &str) -> Res<()> {
+        let merge_tree = Command::new("git")
+            .args(["merge-tree", "--write-tree", "HEAD", rev])
+            .output()
+            .map_err(|err| err.to_string())?;
+
+        // Exit code 1 means the merge has conflicts, not that it failed:
+        // stdout's first line is still a valid (conflicted) tree OID, which
+        // is exactly the case previewing is most useful for. Only bail on
+        // exit codes that mean `merge-tree` couldn't compute a result at all.
+        match merge_tree.status.code() {
+            Some(0) | Some(1) => {}
+            _ => return Err(String::from_utf8_lossy(&merge_tree.stderr).into_owned()),
+        }
+
+        let tree_oid = String::from_utf8_lossy(&merge_tree.stdout)
+            .lines()
+            .next()
+            .ok_or("`git merge-tree` produced no output")?
+            .trim()
+            .to_string();
+
+        let mut cmd = Command::new("git");
+        cmd.args(["diff", "HEAD", &tree_oid]);
+        state.close_menu();
+        state.display_command_output_as_diff(term, cmd)
+    }
+
+    /// Default `-m` text offered when prompting for a merge message:
+    /// `Merge branch '<rev>'` for the selected revision. Unlike `git merge`'s
+    /// own default, it does not vary with the kind of revision (tag, commit,
+    /// remote branch) or with the branch being merged into.
+    fn default_message(state: &State) -> Option<String> {
+        selected_rev(state).map(|rev| format!("Merge branch '{}'", rev))
+    }
+
+    /// Merge the selected revision with a message entered on the spot,
+    /// passed as `-m <msg>`, instead of dropping into `$EDITOR` or
+    /// accepting git's default message.
+    fn message(state: &mut State, term: &mut Term, message: &str) -> Res<()> {
+        let rev = selected_rev(state).ok_or("Revision must be selected")?;
+        // The menu's own `-m` arg (set independently, like `--strategy=`)
+        // must not also be appended, or `git merge` sees two `-m` values
+        // and concatenates them into one mangled message.
+        let args = state.pending_menu.as_ref().unwrap().args();
+        let mut args = args.into_iter();
+        let mut filtered_args = Vec::new();
+        while let Some(arg) = args.next() {
+            if arg == "-m" {
+                args.next();
+                continue;
+            }
+            filtered_args.push(arg);
+        }
+
+        let mut cmd = Command::new("git");
+        cmd.args(["merge", "-m", message]);
+        cmd.args(filtered_args);
+        cmd.args([&rev]);
+        state.close_menu();
+        state.run_cmd_async(term, &[], cmd)
     }
 
     fn squash(state: &mut State, term: &mut Term, rev: &str) -> Res<()> {
@@ -123,8 +254,12 @@ impl MergeAction {
     fn dissolve(state: &mut State, term: &mut Term, destination_branch: &str) -> Res<()> {
         let upstream = git::upstream_branch_name(&state.repo)?;
         push::set_upstream_and_push(state, term, &upstream)?;
+        // Capture the branch being dissolved before `checkout` moves HEAD
+        // to `destination_branch`, or `get_head` below would return the
+        // destination instead of the source.
+        let source_branch = git::get_head(&state.repo);
         checkout::checkout(state, term, destination_branch)?;
-        match git::get_head(&state.repo) {
+        match source_branch {
             Ok(ref name) => MergeAction::absorb(state, term, name),
             // Head is not a branch
             Err(_) => MergeAction::edit(
@@ -143,6 +278,8 @@ impl Display for MergeAction {
             MergeAction::Edit => "Merge and edit message",
             MergeAction::NoCommit => "Merge but don't commit",
             MergeAction::Absorb => "Absorb",
+            MergeAction::Preview => "Preview merge",
+            MergeAction::Message => "Merge with message",
             MergeAction::Squash => "Squash merge",
             MergeAction::Dissolve => "Dissolve",
         })
@@ -175,6 +312,18 @@ impl OpTrait for MergeAction {
                 latest_local_branch,
                 true,
             ),
+            MergeAction::Preview => create_prompt_with_default(
+                "Preview merge",
+                MergeAction::preview,
+                selected_rev,
+                true,
+            ),
+            MergeAction::Message => create_prompt_with_default(
+                "Merge message",
+                MergeAction::message,
+                MergeAction::default_message,
+                true,
+            ),
             MergeAction::Squash => {
                 create_prompt_with_default("Squash", MergeAction::squash, selected_rev, true)
             }
@@ -204,6 +353,7 @@ impl OpTrait for MergeAction {
 ///  :if magit-merge-in-progress-p
 ///  ("m" "Commit merge" magit-commit-create)
 ///  ("a" "Abort merge"  magit-merge-abort)])
+#[derive(Clone, Copy, Debug)]
 enum MergeState {
     Commit,
     Abort,
@@ -237,3 +387,37 @@ impl OpTrait for MergeState {
         }
     }
 }
+
+const MERGE_ACTIONS: &[MergeAction] = &[
+    MergeAction::Plain,
+    MergeAction::Edit,
+    MergeAction::NoCommit,
+    MergeAction::Absorb,
+    MergeAction::Preview,
+    MergeAction::Message,
+    MergeAction::Squash,
+    MergeAction::Dissolve,
+];
+
+const MERGE_STATES: &[MergeState] = &[MergeState::Commit, MergeState::Abort];
+
+/// Returns the items for the merge menu: [`MergeState`] (commit/abort the
+/// ongoing merge) while a merge is in progress, [`MergeAction`] (start a new
+/// merge) otherwise.
+///
+/// Mirrors magit's `:if magit-merge-in-progress-p` split between
+/// `magit-merge` and its "in progress" actions.
+pub(crate) fn list_actions(state: &State) -> Vec<Box<dyn OpTrait>> {
+    if git::merge_in_progress(&state.repo) {
+        MERGE_STATES
+            .iter()
+            .map(|action| Box::new(*action) as Box<dyn OpTrait>)
+            .collect()
+    } else {
+        MERGE_ACTIONS
+            .iter()
+            .cloned()
+            .map(|action| Box::new(action) as Box<dyn OpTrait>)
+            .collect()
+    }
+}